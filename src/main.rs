@@ -1,51 +1,90 @@
-use std::io::{stdout, Stdout};
-use std::sync::mpsc;
+use std::collections::VecDeque;
+use std::io::{stdout, Result, Write as _};
 use std::thread;
 use std::time::Duration;
 
+use chrono::{DateTime, Utc};
 use crossterm::{
     cursor,
-    event::{read, Event, KeyCode, KeyEvent},
+    event::{
+        poll, read, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent,
+        KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+    },
     execute,
     style::Print,
     terminal::{self, ClearType},
-    tty::IsTty,
-    Result,
 };
+use nom::{
+    bytes::complete::{tag, take_until},
+    combinator::map_res,
+    sequence::tuple,
+    IResult,
+};
+use unicode_width::UnicodeWidthChar;
+
+const HISTORY_CAP: usize = 100;
+const LOG_PATH: &str = "guruguru.log";
+const MAX_REPLAY_GAP: Duration = Duration::from_secs(5);
 
-enum State {
-    Pause,
-    Stop,
-    Resume,
-    NewMessage(String),
+// one animation step: a base char plus any zero-width combining marks
+// stacked on it, and the number of terminal columns it occupies (1 or 2).
+#[derive(Clone)]
+struct Glyph {
+    text: String,
+    width: u16,
 }
 
 struct CharGen {
     position: usize,
-    chars: Vec<char>,
+    glyphs: Vec<Glyph>,
 }
 
 impl CharGen {
     pub fn new(chars: &str) -> Self {
-        let chars: Vec<char> = chars.chars().collect();
-        CharGen { position: 0, chars }
+        let glyphs = Self::group_glyphs(chars);
+        CharGen { position: 0, glyphs }
     }
     pub fn update(&mut self, newmsg: &str) {
-        self.chars.clear();
-        let newvec: Vec<char> = newmsg.chars().collect();
-        self.chars.extend(newvec);
-        self.position = self.position % self.chars.len();
+        self.glyphs = Self::group_glyphs(newmsg);
+        self.position %= self.glyphs.len();
+    }
+    // group a string into glyphs, attaching 0-width combining marks to the
+    // previous base char so a base+mark pair advances as one animation step.
+    fn group_glyphs(text: &str) -> Vec<Glyph> {
+        let mut glyphs: Vec<Glyph> = Vec::new();
+        for ch in text.chars() {
+            match UnicodeWidthChar::width(ch) {
+                Some(w) if w > 0 => glyphs.push(Glyph {
+                    text: ch.to_string(),
+                    width: w as u16,
+                }),
+                _ => match glyphs.last_mut() {
+                    Some(last) => last.text.push(ch),
+                    None => glyphs.push(Glyph {
+                        text: ch.to_string(),
+                        width: 1,
+                    }),
+                },
+            }
+        }
+        if glyphs.is_empty() {
+            glyphs.push(Glyph {
+                text: " ".to_string(),
+                width: 1,
+            });
+        }
+        glyphs
     }
 }
 
 impl Iterator for CharGen {
-    type Item = char;
+    type Item = Glyph;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let result = Some(self.chars[self.position]);
-        let size = self.chars.len();
+        let result = self.glyphs[self.position].clone();
+        let size = self.glyphs.len();
         self.position = (self.position + 1) % size;
-        result
+        Some(result)
     }
 }
 
@@ -74,6 +113,34 @@ impl PositionGenerator {
             direction: Direction::Right,
         }
     }
+    // whether a width-2 glyph printed at the current x would be cut off by
+    // the right edge. This only depends on the column, not the direction:
+    // the Down segment sits at x == width - 1 (never fits), the Up segment
+    // sits at x == 0 (always fits), and Right/Left cross every column in
+    // between.
+    pub fn fits_wide_glyph(&self) -> bool {
+        self.x + 1 < self.width
+    }
+    // adopt new terminal dimensions, clamping the current position into the
+    // new range; if the old position fell outside it, the trace sets off
+    // Right from the clamped cell (it is not moved back onto the top edge).
+    pub fn resize(&mut self, width: u16, height: u16) {
+        let out_of_bounds = self.x >= width || self.y >= height;
+        self.width = width;
+        self.height = height;
+        self.x = self.x.min(width.saturating_sub(1));
+        self.y = self.y.min(height.saturating_sub(1));
+        if out_of_bounds {
+            self.direction = Direction::Right;
+        }
+    }
+    // teleport the trace to a clicked/dragged cell, clamped to the current
+    // terminal size, and set it off in the given direction from there.
+    pub fn set_position(&mut self, x: u16, y: u16, direction: Direction) {
+        self.x = x.min(self.width.saturating_sub(1));
+        self.y = y.min(self.height.saturating_sub(1));
+        self.direction = direction;
+    }
 }
 
 impl Iterator for PositionGenerator {
@@ -118,10 +185,187 @@ impl Iterator for PositionGenerator {
     }
 }
 
+// sum of display columns a run of chars takes up, for overflow checks on
+// the input buffer (which may hold wide or zero-width chars).
+fn display_width(chars: &[char]) -> usize {
+    chars
+        .iter()
+        .map(|&ch| UnicodeWidthChar::width(ch).unwrap_or(0))
+        .sum()
+}
+
+// index of the start of the word to the left of `cursor`, skipping any
+// whitespace run immediately before it first.
+fn word_left(chars: &[char], cursor: usize) -> usize {
+    let mut i = cursor;
+    while i > 0 && chars[i - 1].is_whitespace() {
+        i -= 1;
+    }
+    while i > 0 && !chars[i - 1].is_whitespace() {
+        i -= 1;
+    }
+    i
+}
+
+// index just past the word to the right of `cursor`, skipping any
+// whitespace run immediately after it first.
+fn word_right(chars: &[char], cursor: usize) -> usize {
+    let mut i = cursor;
+    while i < chars.len() && chars[i].is_whitespace() {
+        i += 1;
+    }
+    while i < chars.len() && !chars[i].is_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+// pick the edge direction for a clicked/dragged cell by which border it's
+// closest to, so a click near the top runs the trace Right, near the right
+// edge Down, and so on around the rectangle.
+fn direction_for_click(x: u16, y: u16, width: u16, height: u16) -> Direction {
+    let dist_top = y;
+    let dist_bottom = height.saturating_sub(1).saturating_sub(y);
+    let dist_left = x;
+    let dist_right = width.saturating_sub(1).saturating_sub(x);
+    let closest = dist_top.min(dist_bottom).min(dist_left).min(dist_right);
+    if closest == dist_top {
+        Direction::Right
+    } else if closest == dist_right {
+        Direction::Down
+    } else if closest == dist_bottom {
+        Direction::Left
+    } else {
+        Direction::Up
+    }
+}
+
+// a submitted message recorded with the UTC time it was sent, for replay.
+struct LogEntry {
+    time: DateTime<Utc>,
+    message: String,
+}
+
+// `<RFC3339-timestamp>\t<message>`
+fn parse_log_line(input: &str) -> IResult<&str, LogEntry> {
+    let (rest, (time, _)) = tuple((
+        map_res(take_until("\t"), |s: &str| {
+            DateTime::parse_from_rfc3339(s).map(|dt| dt.with_timezone(&Utc))
+        }),
+        tag("\t"),
+    ))(input)?;
+    Ok((
+        "",
+        LogEntry {
+            time,
+            message: rest.to_string(),
+        },
+    ))
+}
+
+// append one submitted message to the log, timestamped with the current
+// UTC time; failures to log are not fatal to the running animation.
+fn append_log(path: &str, message: &str) {
+    let line = format!("{}\t{}\n", Utc::now().to_rfc3339(), message);
+    let file = std::fs::OpenOptions::new().create(true).append(true).open(path);
+    if let Ok(mut file) = file {
+        let _ = file.write_all(line.as_bytes());
+    }
+}
+
+// parse every line of the log, returning the entries in order plus a count
+// of malformed lines that were skipped rather than aborting the replay.
+fn read_log(path: &str) -> Result<(Vec<LogEntry>, usize)> {
+    let content = std::fs::read_to_string(path)?;
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for line in content.lines() {
+        match parse_log_line(line) {
+            Ok((_, entry)) => entries.push(entry),
+            Err(_) => skipped += 1,
+        }
+    }
+    Ok((entries, skipped))
+}
+
+// feed a logged session's messages back into the animation on their
+// original cadence.
+fn replay(path: &str) -> Result<()> {
+    let (entries, skipped) = read_log(path)?;
+
+    let mut out = stdout();
+    terminal::enable_raw_mode()?;
+    let (width, height) = terminal::size().unwrap();
+    execute!(
+        out,
+        terminal::Clear(ClearType::All),
+        cursor::MoveTo(1, 2),
+        Print(format!(
+            "Replaying {} ({} messages, {} skipped). Esc to stop.",
+            path,
+            entries.len(),
+            skipped
+        ))
+    )?;
+
+    let mut pg = PositionGenerator::new(width, height);
+    let mut iter = entries.into_iter().peekable();
+    'replay: while let Some(entry) = iter.next() {
+        let mut cg = CharGen::new(&entry.message);
+        let gap = match iter.peek() {
+            Some(next) => (next.time - entry.time)
+                .to_std()
+                .unwrap_or(Duration::ZERO)
+                .min(MAX_REPLAY_GAP),
+            None => MAX_REPLAY_GAP,
+        };
+        let steps = (gap.as_millis() / 10).max(1);
+        for _ in 0..steps {
+            if poll(Duration::from_millis(0))? {
+                if let Event::Key(KeyEvent {
+                    code: KeyCode::Esc, ..
+                }) = read()?
+                {
+                    break 'replay;
+                }
+            }
+            let glyph = cg.next().unwrap();
+            while glyph.width == 2 && !pg.fits_wide_glyph() {
+                pg.next();
+            }
+            let (x, y) = pg.next().unwrap();
+            execute!(out, cursor::Hide, cursor::MoveTo(x, y), Print(glyph.text))?;
+            if glyph.width == 2 {
+                pg.next();
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    execute!(
+        out,
+        terminal::Clear(ClearType::All),
+        cursor::MoveTo(0, 0),
+        cursor::Show
+    )?;
+    terminal::disable_raw_mode()?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(path) = args
+        .iter()
+        .position(|a| a == "--replay")
+        .and_then(|pos| args.get(pos + 1))
+    {
+        return replay(path);
+    }
+
     let mut out = stdout();
     // to accept typing Esc key
     terminal::enable_raw_mode()?;
+    execute!(out, EnableMouseCapture)?;
     // clear terminal and print help messege
     let reset_terminal = || {
         let mut out = stdout();
@@ -134,62 +378,140 @@ fn main() -> Result<()> {
     };
     reset_terminal()?;
 
-    let (tx, rx) = mpsc::channel::<State>();
-    let _current_position = cursor::position().unwrap(); // unused
+    let (mut width, mut height) = terminal::size().unwrap();
+    let mut inner_width: usize = (width as usize).saturating_sub(2);
+    let mut cg = CharGen::new("hello world.");
+    let mut pg = PositionGenerator::new(width, height);
+    let mut msg: Vec<char> = Vec::new();
+    let mut cursor: usize = 0;
+    let mut kill_buffer: Vec<char> = Vec::new();
+    // the trace only starts once a message has been submitted by Enter
+    let mut started = false;
 
-    let join_handle = thread::spawn(move || -> Result<()> {
-        let (width, height) = terminal::size().unwrap();
-        let mut cg = CharGen::new("hello world.");
-        let mut pg = PositionGenerator::new(width, height);
-        let should_pause = |rx: &mpsc::Receiver<State>| match rx.try_recv() {
-            Ok(State::Pause) => true,
-            _ => false,
-        };
-        let mut is_first = true;
-        loop {
-            if is_first || should_pause(&rx) {
-                is_first = false;
-                // wait for changing state
-                loop {
-                    match rx.recv() {
-                        Ok(State::NewMessage(msg)) => {
-                            cg.update(&msg);
-                            continue;
-                        }
-                        Ok(State::Resume) => break,
-                        Ok(State::Stop) => return Ok(()),
-                        _ => continue,
-                    }
+    let mut history: VecDeque<String> = VecDeque::new();
+    // position while walking history; None means editing the draft directly
+    let mut history_pos: Option<usize> = None;
+    let mut draft: Vec<char> = Vec::new();
+
+    loop {
+        // poll so the same loop drives both the animation and input, rather
+        // than handing the animation off to a separate thread
+        if !poll(Duration::from_millis(10))? {
+            if started {
+                // calc guruguru char position; a wide glyph that wouldn't
+                // fit in the last column skips ahead along the trace until
+                // it reaches a cell where it does.
+                let glyph = cg.next().unwrap();
+                while glyph.width == 2 && !pg.fits_wide_glyph() {
+                    pg.next();
+                }
+                let (x, y) = pg.next().unwrap();
+                execute!(out, cursor::Hide, cursor::MoveTo(x, y), Print(glyph.text))?;
+                // a double-width glyph also covers the column to its right.
+                // The trace steps past one extra cell after it; moving Right
+                // along the top edge that cell is the covered column, on the
+                // other edges it is not.
+                if glyph.width == 2 {
+                    pg.next();
                 }
             }
-            // calc guruguru char position
-            let (x, y) = pg.next().unwrap();
-            execute!(
-                out,
-                cursor::Hide,
-                cursor::MoveTo(x, y),
-                Print(cg.next().unwrap())
-            )?;
-            thread::sleep(Duration::from_millis(10));
+            continue;
         }
-    });
 
-    // read input event
-    let mut out = stdout();
-    let mut msg: Vec<char> = Vec::new();
-    loop {
         let event = read().unwrap();
         match event {
-            Event::Key(KeyEvent { code, .. }) => {
-                _ = tx.send(State::Pause);
-                let (width, _) = terminal::size().unwrap();
-                let inner_width: usize = (width - 2) as usize;
-
+            Event::Resize(w, h) => {
+                width = w;
+                height = h;
+                inner_width = (width as usize).saturating_sub(2);
+                pg.resize(width, height);
+                reset_terminal()?;
+            }
+            // a click teleports the trace's start; a drag keeps doing the
+            // same on every move so the trace follows the pointer until the
+            // button is released
+            Event::Mouse(MouseEvent {
+                kind:
+                    MouseEventKind::Down(MouseButton::Left) | MouseEventKind::Drag(MouseButton::Left),
+                column,
+                row,
+                ..
+            }) => {
+                let direction = direction_for_click(column, row, width, height);
+                pg.set_position(column, row, direction);
+            }
+            Event::Key(KeyEvent {
+                code, modifiers, ..
+            }) => {
                 // clear 1st line (inside)
                 match code {
+                    KeyCode::Char('a') if modifiers.contains(KeyModifiers::CONTROL) => {
+                        cursor = 0;
+                    }
+                    KeyCode::Char('e') if modifiers.contains(KeyModifiers::CONTROL) => {
+                        cursor = msg.len();
+                    }
+                    KeyCode::Char('k') if modifiers.contains(KeyModifiers::CONTROL) => {
+                        kill_buffer = msg.split_off(cursor);
+                        history_pos = None;
+                    }
+                    KeyCode::Char('y') if modifiers.contains(KeyModifiers::CONTROL) => {
+                        if display_width(&msg) + display_width(&kill_buffer) < inner_width {
+                            msg.splice(cursor..cursor, kill_buffer.iter().copied());
+                            cursor += kill_buffer.len();
+                            history_pos = None;
+                        }
+                    }
                     KeyCode::Char(ch) => {
-                        if msg.len() + 1 < inner_width {
-                            msg.push(ch);
+                        let ch_width = UnicodeWidthChar::width(ch).unwrap_or(0);
+                        if display_width(&msg) + ch_width + 1 < inner_width {
+                            msg.insert(cursor, ch);
+                            cursor += 1;
+                            history_pos = None;
+                        }
+                    }
+                    KeyCode::Left if modifiers.contains(KeyModifiers::CONTROL) => {
+                        cursor = word_left(&msg, cursor);
+                    }
+                    KeyCode::Right if modifiers.contains(KeyModifiers::CONTROL) => {
+                        cursor = word_right(&msg, cursor);
+                    }
+                    KeyCode::Left => {
+                        cursor = cursor.saturating_sub(1);
+                    }
+                    KeyCode::Right => {
+                        cursor = (cursor + 1).min(msg.len());
+                    }
+                    KeyCode::Home => {
+                        cursor = 0;
+                    }
+                    KeyCode::End => {
+                        cursor = msg.len();
+                    }
+                    KeyCode::Up => {
+                        if !history.is_empty() {
+                            let next_pos = match history_pos {
+                                None => {
+                                    draft = msg.clone();
+                                    history.len() - 1
+                                }
+                                Some(pos) => pos.saturating_sub(1),
+                            };
+                            msg = history[next_pos].chars().collect();
+                            cursor = msg.len();
+                            history_pos = Some(next_pos);
+                        }
+                    }
+                    KeyCode::Down => {
+                        if let Some(pos) = history_pos {
+                            if pos + 1 < history.len() {
+                                msg = history[pos + 1].chars().collect();
+                                history_pos = Some(pos + 1);
+                            } else {
+                                msg = draft.clone();
+                                history_pos = None;
+                            }
+                            cursor = msg.len();
                         }
                     }
                     KeyCode::Enter => {
@@ -199,25 +521,45 @@ fn main() -> Result<()> {
                             msg.extend(defaultchars);
                         }
                         let newmessage: String = msg.clone().into_iter().collect();
-                        _ = tx.send(State::NewMessage(newmessage));
+                        if history.back() != Some(&newmessage) {
+                            history.push_back(newmessage.clone());
+                            if history.len() > HISTORY_CAP {
+                                history.pop_front();
+                            }
+                        }
+                        append_log(LOG_PATH, &newmessage);
+                        cg.update(&newmessage);
+                        started = true;
                         msg.clear();
-                        _ = tx.send(State::Resume);
+                        cursor = 0;
+                        draft.clear();
+                        history_pos = None;
                         continue;
                     }
                     KeyCode::Esc => {
                         // stop by esc key
                         execute!(out, cursor::MoveTo(1, 1), Print("prepare for exiting..."))?;
-                        _ = tx.send(State::Stop);
-                        _ = join_handle.join();
                         break;
                     }
                     KeyCode::Backspace => {
-                        msg.pop();
+                        if cursor > 0 {
+                            msg.remove(cursor - 1);
+                            cursor -= 1;
+                            history_pos = None;
+                        }
+                    }
+                    KeyCode::Delete => {
+                        if cursor < msg.len() {
+                            msg.remove(cursor);
+                            history_pos = None;
+                        }
                     }
                     _ => {
                         let newchars: Vec<char> = format!("{:?}", code).chars().collect();
-                        if msg.len() + newchars.len() < inner_width {
-                            msg.extend(newchars);
+                        if display_width(&msg) + display_width(&newchars) < inner_width {
+                            msg.splice(cursor..cursor, newchars.iter().copied());
+                            cursor += newchars.len();
+                            history_pos = None;
                         }
                     }
                 }
@@ -230,10 +572,22 @@ fn main() -> Result<()> {
                     cursor::Show
                 );
 
-                let s: String = msg.iter().take(inner_width).collect();
+                // truncate by display columns, not char count, so a wide
+                // glyph near the edge doesn't get split across the border.
+                let mut acc = 0usize;
+                let s: String = msg
+                    .iter()
+                    .take_while(|&&ch| {
+                        acc += UnicodeWidthChar::width(ch).unwrap_or(0);
+                        acc <= inner_width
+                    })
+                    .collect();
 
-                // show msg
+                // show msg, then put the terminal cursor back where the
+                // user's edit point actually is
                 execute!(out, cursor::MoveTo(1, 1), Print(s))?;
+                let cursor_col = 1 + display_width(&msg[..cursor]) as u16;
+                execute!(out, cursor::MoveTo(cursor_col, 1))?;
             }
             _ => (),
         }
@@ -244,7 +598,8 @@ fn main() -> Result<()> {
         out,
         terminal::Clear(ClearType::All),
         cursor::MoveTo(0, 0),
-        cursor::Show
+        cursor::Show,
+        DisableMouseCapture
     )?;
     terminal::disable_raw_mode()?;
     Ok(())